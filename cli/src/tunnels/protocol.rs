@@ -6,11 +6,125 @@ use std::collections::HashMap;
 
 use crate::options::Quality;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Well-known `ResponseError` codes used outside of the generic transport
+/// error path.
+pub const CODE_NO_VERSION_OVERLAP: i32 = -32001;
+/// Returned for a batched call whose `ResultReference` pointed at a call
+/// that errored, or whose `path` did not resolve against that call's result.
+pub const CODE_BATCH_REFERENCE_UNRESOLVED: i32 = -32002;
+
+#[derive(Deserialize, Debug)]
+pub struct BatchParams {
+	/// Calls to run, in order. Each is dispatched as if sent on its own,
+	/// except that its params may embed `ResultReference`s.
+	pub calls: Vec<BatchCall>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BatchCall {
+	/// Caller-chosen id other calls in the batch can reference.
+	pub invocation_id: u32,
+	#[serde(flatten)]
+	pub method: ServerRequestMethod,
+}
+
+/// A back-reference to a field of an earlier call's result within the
+/// same `batch`, resolved server-side before the referencing call runs.
+/// Appears in place of a literal value anywhere a call's params allow it,
+/// e.g. `{ "invocation_id": 1, "path": "port" }` in place of a port number.
+#[derive(Deserialize, Debug)]
+pub struct ResultReference {
+	pub invocation_id: u32,
+	/// Name of the field to read off the referenced call's result, e.g. `port`.
+	pub path: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchResult {
+	/// Responses in the same order as the originating `calls`.
+	pub responses: Vec<BatchCallResponse>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BatchCallResponse {
+	pub invocation_id: u32,
+	#[serde(flatten)]
+	pub outcome: BatchCallOutcome,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+pub enum BatchCallOutcome {
+	Success { result: Value },
+	Error { error: ResponseError },
+}
+
+/// Wire encoding used for messages sent after a `handshake`. Both sides
+/// always understand `Json`; `MessagePack` may be selected to avoid the
+/// bloat JSON imposes on the `serde_bytes` fields used by `servermsg`,
+/// `httpbody`, and `callserverhttp` — under `Json` those fields serialize
+/// as an array of byte values, versus compact bytes under `MessagePack`.
+///
+/// The adjacently-tagged enums and `#[serde(flatten)]`/`untagged` structs
+/// in this file (`ServerRequestMethod`, `ToServerRequest`, `BatchCall`,
+/// `ValueOrRef`, `BatchCallOutcome`, ...) need a self-describing,
+/// field-name-preserving codec. A `MessagePack` implementation MUST encode
+/// structs as maps, not arrays — `rmp_serde`'s array-by-default mode will
+/// not round-trip these types.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+	Json,
+	MessagePack,
+}
+
+/// Sent by the initiating side before any other RPC to agree on a protocol
+/// version. The responder picks the highest version it also supports from
+/// `supported_versions` (ordered most-preferred first) and echoes it back
+/// in `HandshakeResult`, or returns a `ResponseError` with
+/// `CODE_NO_VERSION_OVERLAP` if there is no overlap.
+#[derive(Deserialize, Debug)]
+pub struct HandshakeParams {
+	pub supported_versions: Vec<u32>,
+	/// Opaque identifier for a previously established session, or an empty
+	/// string for a fresh one.
+	pub client_id: String,
+	/// Encodings the sender can read, most-preferred first. Assumed to be
+	/// `[Json]` if omitted, for peers that predate encoding negotiation.
+	#[serde(default = "default_encodings")]
+	pub supported_encodings: Vec<Encoding>,
+	/// Free-form data reserved for future extensions.
+	#[serde(default)]
+	pub extra: HashMap<String, String>,
+}
+
+fn default_encodings() -> Vec<Encoding> {
+	vec![Encoding::Json]
+}
+
+#[derive(Serialize, Debug)]
+pub struct HandshakeResult {
+	/// The highest protocol version supported by both sides.
+	pub version: u32,
+	/// The encoding all subsequent messages on this connection will use.
+	pub encoding: Encoding,
+}
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "method", content = "params")]
 #[allow(non_camel_case_types)]
 pub enum ServerRequestMethod {
+	/// Negotiates the protocol version to use for the rest of the
+	/// connection. Peers that predate this method never send it. This enum
+	/// has no `version` variant: the legacy one-shot `version` message is
+	/// only sent from this side, as `ClientRequestMethod::version`.
+	handshake(HandshakeParams),
+	/// Runs a batch of calls in order in a single round trip. A later call
+	/// may use a `ResultReference` in place of a literal value to read a
+	/// field out of an earlier call's result.
+	batch(BatchParams),
 	/// Request from the client to start the VS Code server. It will download the
 	/// requested version, if necessary.
 	serve(ServeParams),
@@ -22,8 +136,20 @@ pub enum ServerRequestMethod {
 	forward(ForwardParams),
 	/// Stops forwarding a port from the machine the CLI is running on.
 	unforward(UnforwardParams),
-	/// Gets the hostname of the machine the CLI is running on.
+	/// Gracefully restarts the managed VS Code server without killing the
+	/// CLI transport.
+	restart(EmptyResult),
+	/// Cleanly terminates the managed VS Code server without killing the
+	/// CLI transport.
+	stop(EmptyResult),
+	/// Gets the hostname of the machine the CLI is running on. A thin alias
+	/// over `serverinfo` kept for back-compat.
 	gethostname(EmptyResult),
+	/// Gets a full self-description of the running CLI/server: versions,
+	/// platform, currently forwarded ports, and negotiated capabilities.
+	/// Lets a client learn everything needed to render a connection status
+	/// UI, and whether an `update` is warranted, in one round trip.
+	serverinfo(EmptyResult),
 	/// Checks for or applies an update to the CLI.
 	update(UpdateParams),
 	/// Sent when the remote instance of VS Code has a message for the server.
@@ -34,6 +160,8 @@ pub enum ServerRequestMethod {
 	httpheaders(HttpHeadersParams),
 	/// Sent (repeatedly) with data in response to an `makehttpreq` from the server.
 	httpbody(HttpBodyParams),
+	/// Sent by the client to upload a panic report to the CLI.
+	crashreport(CrashReportParams),
 }
 
 #[derive(Serialize, Debug)]
@@ -41,9 +169,63 @@ pub enum ServerRequestMethod {
 #[allow(non_camel_case_types)]
 pub enum ClientRequestMethod<'a> {
 	servermsg(RefServerMessageParams<'a>),
-	serverlog(ServerLog<'a>),
 	makehttpreq(HttpRequestParams<'a>),
 	version(VersionParams),
+	/// Uploads a panic from the spawned VS Code server.
+	crashreport(CrashReportParams),
+}
+
+/// A panic report, with each backtrace frame demangled (via
+/// `rustc_demangle`) before it is sent so symbols are readable on arrival.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CrashReportParams {
+	/// Monotonically increasing id, scoped to the process that panicked.
+	pub report_id: u32,
+	pub message: String,
+	/// Demangled stack frames, outermost first.
+	pub backtrace: Vec<String>,
+	pub commit_id: Option<String>,
+	pub quality: Quality,
+	pub os: String,
+	pub arch: String,
+	/// Unix timestamp, coarse to the second.
+	pub timestamp: u64,
+}
+
+/// A fire-and-forget notification from the server to the client. Unlike
+/// `ToClientRequest`, an event carries no `id` and never gets a response,
+/// so a peer never mistakenly waits on one.
+#[derive(Serialize, Debug)]
+pub struct ToClientEvent<'a> {
+	#[serde(flatten)]
+	pub event: ServerEvent<'a>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", content = "body")]
+#[allow(non_camel_case_types)]
+pub enum ServerEvent<'a> {
+	serverlog(ServerLog<'a>),
+	port_forwarded(PortForwardedEvent),
+	server_exited(ServerExitedEvent),
+	update_available(UpdateAvailableEvent),
+}
+
+#[derive(Serialize, Debug)]
+pub struct PortForwardedEvent {
+	pub port: u16,
+	pub uri: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ServerExitedEvent {
+	/// Process exit code, or `None` if the server was killed by a signal.
+	pub code: Option<i32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UpdateAvailableEvent {
+	pub version: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -70,7 +252,50 @@ pub struct HttpHeadersParams {
 
 #[derive(Deserialize, Debug)]
 pub struct ForwardParams {
-	pub port: u16,
+	/// Port to forward, or a reference to one produced by an earlier call
+	/// in the same `batch` (e.g. the port `serve` chose).
+	pub port: ValueOrRef<u16>,
+	/// Human-readable label for this forward, echoed back in `ForwardResult`.
+	pub label: Option<String>,
+	/// Hint for the protocol spoken on `port`.
+	pub protocol: Option<ForwardProtocol>,
+	/// Basic-auth credentials gating the forwarded endpoint.
+	pub auth: Option<ForwardAuth>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardProtocol {
+	Http,
+	Https,
+	Tcp,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ForwardAuth {
+	pub username: String,
+	pub password: SecretBytes,
+}
+
+/// Bytes that never appear in `Debug` output, for credentials carried over
+/// the wire (an array of byte values under the `Json` encoding, compact
+/// bytes under `MessagePack`).
+#[derive(Deserialize, Clone)]
+pub struct SecretBytes(#[serde(with = "serde_bytes")] pub Vec<u8>);
+
+impl std::fmt::Debug for SecretBytes {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str("SecretBytes(..)")
+	}
+}
+
+/// A parameter that may be given literally, or as a `ResultReference`
+/// resolved against an earlier call's result when used inside a `batch`.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ValueOrRef<T> {
+	Value(T),
+	Ref(ResultReference),
 }
 
 #[derive(Deserialize, Debug)]
@@ -81,6 +306,7 @@ pub struct UnforwardParams {
 #[derive(Serialize)]
 pub struct ForwardResult {
 	pub uri: String,
+	pub label: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -93,6 +319,11 @@ pub struct ServeParams {
 	pub use_local_download: bool,
 }
 
+#[derive(Serialize)]
+pub struct ServeResult {
+	pub port: u16,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct EmptyResult {}
 
@@ -123,13 +354,21 @@ pub struct UpdateResult {
 
 #[derive(Deserialize, Debug)]
 pub struct ToServerRequest {
+	/// `None` only for peers predating the `handshake` negotiation; every
+	/// method added after it is expected to carry an id.
 	pub id: Option<u32>,
 	#[serde(flatten)]
 	pub params: ServerRequestMethod,
 }
 
+/// A correlated request awaiting a `SuccessResponse` or `ErrorResponse`.
+/// Fire-and-forget notifications go through `ToClientEvent` instead; `id`
+/// stays optional only so the legacy one-shot `version` message (sent
+/// before peers could negotiate a `handshake`) can still be sent without
+/// one; a `None` id is left out of the serialized message.
 #[derive(Serialize, Debug)]
 pub struct ToClientRequest<'a> {
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub id: Option<u32>,
 	#[serde(flatten)]
 	pub params: ClientRequestMethod<'a>,
@@ -167,6 +406,20 @@ pub struct GetHostnameResponse {
 	pub value: String,
 }
 
+#[derive(Serialize)]
+pub struct ServerInfoResult {
+	pub hostname: String,
+	pub version: &'static str,
+	pub protocol_version: u32,
+	pub commit_id: Option<String>,
+	pub quality: Quality,
+	pub os: String,
+	pub arch: String,
+	pub forwarded_ports: Vec<ForwardResult>,
+	/// Wire encoding negotiated for this connection.
+	pub encoding: Encoding,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct CallServerHttpParams {
 	pub path: String,
@@ -183,6 +436,9 @@ pub struct CallServerHttpResult {
 	pub headers: HashMap<String, String>,
 }
 
+/// Legacy one-shot version announcement, predating `handshake`. Still
+/// sent to the client as `ClientRequestMethod::version`; this side never
+/// receives one.
 #[derive(Serialize, Debug)]
 pub struct VersionParams {
 	pub version: &'static str,